@@ -7,6 +7,9 @@ use clap::ArgMatches;
 use rpassword;
 
 use crate::error::Result;
+use crate::mongodb::AuthMechanism;
+use crate::mongodb::OplogFilters;
+use crate::oplog_stats::OutputFormat;
 
 /// Tool arguments.
 #[derive(Debug)]
@@ -26,11 +29,33 @@ pub struct Args {
     /// Name of the database to use for authentication.
     pub auth_db: Option<String>,
 
+    /// MongoDB connection string. When given, it takes precedence over
+    /// `host`/`port`/`username`/`password`/`auth_db`.
+    pub uri: Option<String>,
+
+    /// Authentication mechanism to explicitly select, rather than relying on
+    /// server negotiation.
+    pub auth_mechanism: Option<AuthMechanism>,
+
     /// Maximal number of documents in the oplog to process.
     pub limit: Option<u64>,
 
     /// Print statistics every time `N` documents have been processed.
     pub print_after: Option<u64>,
+
+    /// Namespace/operation filters to apply to the oplog query.
+    pub filters: OplogFilters,
+
+    /// Keep tailing the oplog and reporting statistics instead of exiting
+    /// once the initial batch has been processed.
+    pub follow: bool,
+
+    /// Format in which to print the statistics.
+    pub format: OutputFormat,
+
+    /// When given, serve statistics over HTTP at this address in Prometheus
+    /// text exposition format instead of printing once and exiting.
+    pub serve_addr: Option<String>,
 }
 
 /// Parses tool arguments.
@@ -85,6 +110,24 @@ pub fn parse_args() -> Result<Args> {
                 .takes_value(true)
                 .display_order(5)
         )
+        .arg(
+            Arg::with_name("uri")
+                .long("uri")
+                .value_name("connection-string")
+                .help("MongoDB connection string (e.g. mongodb://... or mongodb+srv://...), taking precedence over --host/--port/--username/--password/--authenticationDatabase")
+                .long_help("MongoDB connection string (e.g. mongodb://... or mongodb+srv://...). Use this to reach replica-set seed lists, TLS-secured deployments, or SRV-discovered clusters that --host/--port cannot express. Takes precedence over --host/--port/--username/--password/--authenticationDatabase")
+                .takes_value(true)
+                .display_order(6)
+        )
+        .arg(
+            Arg::with_name("auth_mechanism")
+                .long("authenticationMechanism")
+                .value_name("mechanism")
+                .help("Authentication mechanism to use, instead of relying on server negotiation")
+                .takes_value(true)
+                .possible_values(&["SCRAM-SHA-256", "SCRAM-SHA-1", "MONGODB-X509"])
+                .display_order(7)
+        )
         .arg(
             Arg::with_name("limit")
                 .short("l")
@@ -92,7 +135,7 @@ pub fn parse_args() -> Result<Args> {
                 .value_name("n")
                 .help("Maximal number of documents in the oplog to process")
                 .takes_value(true)
-                .display_order(6)
+                .display_order(8)
         )
         .arg(
             Arg::with_name("print_after")
@@ -100,7 +143,54 @@ pub fn parse_args() -> Result<Args> {
                 .value_name("n")
                 .help("Print statistics every time n documents have been processed")
                 .takes_value(true)
-                .display_order(7)
+                .display_order(9)
+        )
+        .arg(
+            Arg::with_name("follow")
+                .short("f")
+                .long("follow")
+                .help("Keep tailing the oplog and keep reporting statistics instead of exiting once the initial batch has been processed")
+                .takes_value(false)
+                .display_order(10)
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("format")
+                .help("Format in which to print the statistics")
+                .takes_value(true)
+                .possible_values(&["table", "json", "csv"])
+                .default_value("table")
+                .display_order(11)
+        )
+        .arg(
+            Arg::with_name("ns")
+                .long("ns")
+                .value_name("database.collection")
+                .help("Only consider oplog entries for this namespace (exact match, or a regex when wrapped in slashes, e.g. /^mydb\\./); repeatable")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .display_order(12)
+        )
+        .arg(
+            Arg::with_name("op")
+                .long("op")
+                .value_name("op")
+                .help("Only consider oplog entries with this operation code; repeatable")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&["i", "u", "d", "c", "n"])
+                .display_order(13)
+        )
+        .arg(
+            Arg::with_name("serve")
+                .long("serve")
+                .value_name("addr")
+                .help("Serve statistics over HTTP at addr (e.g. 0.0.0.0:9898), refreshed on every scrape, in Prometheus text exposition format, instead of printing once and exiting")
+                .takes_value(true)
+                .display_order(14)
         )
         .get_matches();
     let limit = get_limit(&matches)?;
@@ -110,6 +200,12 @@ pub fn parse_args() -> Result<Args> {
     let username = get_username(&matches);
     let password = get_password(&matches, username.is_some())?;
     let auth_db = get_auth_db(&matches);
+    let uri = get_uri(&matches);
+    let auth_mechanism = get_auth_mechanism(&matches);
+    let follow = get_follow(&matches);
+    let format = get_format(&matches);
+    let filters = get_filters(&matches);
+    let serve_addr = get_serve_addr(&matches);
 
     Ok(Args {
         host,
@@ -117,8 +213,14 @@ pub fn parse_args() -> Result<Args> {
         username,
         password,
         auth_db,
+        uri,
+        auth_mechanism,
         limit,
         print_after,
+        filters,
+        follow,
+        format,
+        serve_addr,
     })
 }
 
@@ -172,3 +274,44 @@ fn get_password(matches: &ArgMatches, username_given: bool) -> Result<Option<Str
 fn get_auth_db(matches: &ArgMatches) -> Option<String> {
     matches.value_of("auth_db").map(String::from)
 }
+
+fn get_uri(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("uri").map(String::from)
+}
+
+fn get_auth_mechanism(matches: &ArgMatches) -> Option<AuthMechanism> {
+    matches.value_of("auth_mechanism").map(|s| match s {
+        "SCRAM-SHA-256" => AuthMechanism::ScramSha256,
+        "SCRAM-SHA-1" => AuthMechanism::ScramSha1,
+        "MONGODB-X509" => AuthMechanism::MongoDbX509,
+        _ => unreachable!("clap should have already validated the value against possible_values"),
+    })
+}
+
+fn get_follow(matches: &ArgMatches) -> bool {
+    matches.is_present("follow")
+}
+
+fn get_format(matches: &ArgMatches) -> OutputFormat {
+    matches
+        .value_of("format")
+        .expect("should never happen (format should have a default value)")
+        .parse()
+        .expect("clap should have already validated the value against possible_values")
+}
+
+fn get_filters(matches: &ArgMatches) -> OplogFilters {
+    let ns = matches
+        .values_of("ns")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    let op = matches
+        .values_of("op")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    OplogFilters { ns, op }
+}
+
+fn get_serve_addr(matches: &ArgMatches) -> Option<String> {
+    matches.value_of("serve").map(String::from)
+}
@@ -4,6 +4,7 @@
 use std::process;
 
 use chrono::Local;
+use failure::Fail;
 use failure::ResultExt;
 use indicatif::ProgressBar;
 
@@ -11,13 +12,17 @@ mod args;
 mod mongodb;
 mod error;
 mod oplog_stats;
+mod serve;
 mod utils;
 
 use crate::args::parse_args;
 use crate::mongodb::MongoDB;
+use crate::mongodb::OplogFilters;
 use crate::error::print_error;
 use crate::error::Result;
+use crate::oplog_stats::print_status_line;
 use crate::oplog_stats::OplogStats;
+use crate::oplog_stats::OutputFormat;
 
 /// Returns the document limit to use for querying the oplog and generating
 /// statistics.
@@ -47,36 +52,184 @@ fn limit_to_use(user_limit: Option<u64>, mongodb: &MongoDB) -> Result<u64> {
 /// * `limit` - Maximal number of documents to process.
 /// * `print_after` - When given, print the statistics after each N processed
 ///   documents.
-fn obtain_oplog_stats(
+/// * `format` - Format in which to print the statistics.
+/// * `filters` - Namespace/operation filters to narrow down the query.
+pub(crate) fn obtain_oplog_stats(
     oplog_stats: &mut OplogStats,
     mongodb: &MongoDB,
     limit: u64,
     print_after: Option<u64>,
+    format: OutputFormat,
+    filters: &OplogFilters,
 ) -> Result<()> {
     let pbar = ProgressBar::new(limit);
-    for result in mongodb.generate_documents_in_oplog(limit)? {
+    for result in mongodb.generate_documents_in_oplog(limit, filters)? {
         let doc = result.context("failed to get a document from the oplog")?;
         oplog_stats
             .update(&doc)
             .context("failed to add info from an oplog document")?;
         pbar.inc(1);
 
-        let processed_doc_count = oplog_stats.get_processed_doc_count();
-        if let Some(print_after) = print_after {
-            if processed_doc_count % print_after == 0 {
-                println!();
-                println!(
+        print_stats_if_needed(oplog_stats, print_after, format);
+    }
+    pbar.finish();
+    Ok(())
+}
+
+/// Obtains statistics about the oplog via the server-side `$group`
+/// aggregation, which only transfers a handful of grouped rows instead of
+/// every document.
+///
+/// # Arguments
+///
+/// * `oplog_stats` - Statistics to fill.
+/// * `mongodb` - Access to a MongoDB instance.
+/// * `limit` - Maximal number of (most recent) documents to aggregate over.
+/// * `print_after` - When given, print the statistics whenever the number of
+///   processed documents, which grows by a whole group's document count with
+///   each merged group, is a multiple of N.
+/// * `format` - Format in which to print the statistics.
+/// * `filters` - Namespace/operation filters to narrow down the query.
+pub(crate) fn obtain_oplog_stats_via_aggregation(
+    oplog_stats: &mut OplogStats,
+    mongodb: &MongoDB,
+    limit: u64,
+    print_after: Option<u64>,
+    format: OutputFormat,
+    filters: &OplogFilters,
+) -> Result<()> {
+    for result in mongodb.generate_oplog_stats_aggregation(limit, filters)? {
+        let group = result.context("failed to get an aggregation result from the oplog")?;
+        oplog_stats
+            .merge_aggregated(&group)
+            .context("failed to merge an aggregated oplog group")?;
+
+        print_stats_if_needed(oplog_stats, print_after, format);
+    }
+    Ok(())
+}
+
+/// Obtains statistics about the oplog, preferring the fast server-side
+/// aggregation path and transparently falling back to streaming every
+/// document when the server is too old to support it (no `$bsonSize`).
+///
+/// # Arguments
+///
+/// * `oplog_stats` - Statistics to fill.
+/// * `mongodb` - Access to a MongoDB instance.
+/// * `limit` - Maximal number of documents to process.
+/// * `print_after` - When given, print the statistics after each N processed
+///   documents.
+/// * `format` - Format in which to print the statistics.
+/// * `filters` - Namespace/operation filters to narrow down the query.
+pub(crate) fn obtain_oplog_stats_preferring_aggregation(
+    oplog_stats: &mut OplogStats,
+    mongodb: &MongoDB,
+    limit: u64,
+    print_after: Option<u64>,
+    format: OutputFormat,
+    filters: &OplogFilters,
+) -> Result<()> {
+    match obtain_oplog_stats_via_aggregation(
+        oplog_stats,
+        mongodb,
+        limit,
+        print_after,
+        format,
+        filters,
+    ) {
+        Ok(()) => Ok(()),
+        Err(err) if is_bson_size_unsupported(&err) => {
+            eprintln!(
+                "warning: server does not support $bsonSize aggregation ({}); \
+                 falling back to streaming all documents",
+                err
+            );
+            *oplog_stats = OplogStats::new();
+            obtain_oplog_stats(oplog_stats, mongodb, limit, print_after, format, filters)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns whether the given error looks like it was caused by the server
+/// not recognizing the `$bsonSize` aggregation operator (MongoDB older than
+/// 4.4).
+///
+/// The server's actual message lives deep in the cause chain (the outermost
+/// error is just `.context(...)` added along the way up), so the whole
+/// chain has to be walked rather than just `err.to_string()`.
+fn is_bson_size_unsupported(err: &failure::Error) -> bool {
+    err.as_fail()
+        .iter_chain()
+        .any(|cause| cause.to_string().contains("bsonSize"))
+}
+
+/// Continuously tails the oplog of the given MongoDB instance, updating
+/// `oplog_stats` with every new document and re-printing the statistics on
+/// the `print_after` cadence. Never returns on its own; the tool has to be
+/// interrupted (e.g. with Ctrl-C) to stop following.
+///
+/// # Arguments
+///
+/// * `oplog_stats` - Statistics to fill.
+/// * `mongodb` - Access to a MongoDB instance.
+/// * `print_after` - When given, print the statistics after each N processed
+///   documents.
+/// * `format` - Format in which to print the statistics.
+/// * `filters` - Namespace/operation filters to narrow down the query.
+fn follow_oplog_stats(
+    oplog_stats: &mut OplogStats,
+    mongodb: &MongoDB,
+    print_after: Option<u64>,
+    format: OutputFormat,
+    filters: &OplogFilters,
+) -> Result<()> {
+    loop {
+        for result in mongodb.generate_documents_in_oplog_tailing(filters)? {
+            let doc = result.context("failed to get a document from the oplog")?;
+            oplog_stats
+                .update(&doc)
+                .context("failed to add info from an oplog document")?;
+
+            print_stats_if_needed(oplog_stats, print_after, format);
+        }
+    }
+}
+
+/// Prints the current statistics when `processed_doc_count` has just become a
+/// multiple of `print_after`.
+fn print_stats_if_needed(oplog_stats: &OplogStats, print_after: Option<u64>, format: OutputFormat) {
+    let processed_doc_count = oplog_stats.get_processed_doc_count();
+    if let Some(print_after) = print_after {
+        if processed_doc_count % print_after == 0 {
+            print_status_line(format, "");
+            print_status_line(
+                format,
+                &format!(
                     "Processed {} documents at {}",
                     processed_doc_count,
                     Local::now()
-                );
-                oplog_stats.print();
-                println!();
-            }
+                ),
+            );
+            oplog_stats.print(format);
+            print_status_line(format, "");
+        }
+    }
+}
+
+/// Prints the estimated replication window, falling back to reporting it
+/// without the capped-collection fill ratio when `collStats` fails (e.g. the
+/// user lacks the privilege to run it).
+fn print_replication_window(oplog_stats: &OplogStats, mongodb: &MongoDB, format: OutputFormat) {
+    print_status_line(format, "Replication window:");
+    match mongodb.get_oplog_coll_stats() {
+        Ok(coll_stats) => oplog_stats.print_replication_window(Some(&coll_stats), format),
+        Err(err) => {
+            eprintln!("warning: failed to get oplog collection stats: {}", err);
+            oplog_stats.print_replication_window(None, format);
         }
     }
-    pbar.finish();
-    Ok(())
 }
 
 /// Runs the tool.
@@ -85,22 +238,57 @@ fn run() -> Result<()> {
     let mongodb = MongoDB::from_args(&args)?;
 
     let limit = limit_to_use(args.limit, &mongodb)?;
-    println!("Obtaining stats (limit: {})...", limit);
+
+    if let Some(addr) = &args.serve_addr {
+        return crate::serve::serve(addr, &mongodb, limit, &args.filters);
+    }
+
+    print_status_line(args.format, &format!("Obtaining stats (limit: {})...", limit));
 
     let mut oplog_stats = OplogStats::new();
-    match obtain_oplog_stats(&mut oplog_stats, &mongodb, limit, args.print_after) {
+    let result = obtain_oplog_stats_preferring_aggregation(
+        &mut oplog_stats,
+        &mongodb,
+        limit,
+        args.print_after,
+        args.format,
+        &args.filters,
+    )
+    .and_then(|_| {
+        if args.follow {
+            print_status_line(
+                args.format,
+                "Initial batch processed; following the oplog for new entries...",
+            );
+            follow_oplog_stats(
+                &mut oplog_stats,
+                &mongodb,
+                args.print_after,
+                args.format,
+                &args.filters,
+            )
+        } else {
+            Ok(())
+        }
+    });
+    match result {
         Ok(_) => {
-            println!(
-                "Final stats after processing {} documents:",
-                oplog_stats.get_processed_doc_count()
+            print_status_line(
+                args.format,
+                &format!(
+                    "Final stats after processing {} documents:",
+                    oplog_stats.get_processed_doc_count()
+                ),
             );
-            oplog_stats.print();
+            oplog_stats.print(args.format);
+            print_status_line(args.format, "");
+            print_replication_window(&oplog_stats, &mongodb, args.format);
             Ok(())
         }
         Err(err) => {
             if oplog_stats.processed_at_least_one_doc() {
-                println!("Obtaining failed; showing last stats:");
-                oplog_stats.print();
+                print_status_line(args.format, "Obtaining failed; showing last stats:");
+                oplog_stats.print(args.format);
             }
             Err(err)
         }
@@ -1,18 +1,86 @@
 //! Access to MongoDB.
 
 use bson::doc;
+use bson::Bson;
 use bson::Document;
+use bson::Regex;
 use failure::ResultExt;
+use mongodb::options::AggregateOptions;
 use mongodb::options::ClientOptions;
 use mongodb::options::Credential;
+use mongodb::options::CursorType;
 use mongodb::options::FindOptions;
+use mongodb::options::Hint;
 use mongodb::options::ServerAddress;
 use mongodb::sync::Client;
 use mongodb::sync::Collection;
 use mongodb::sync::Cursor;
 
+pub use mongodb::options::AuthMechanism;
+
 use crate::args::Args;
 use crate::error::Result;
+use crate::utils::get_numeric_field;
+
+/// Capped-collection statistics for `oplog.rs`, as reported by the server's
+/// `collStats` command.
+#[derive(Debug, Clone, Copy)]
+pub struct OplogCollStats {
+    /// Maximum size (in bytes) to which the capped collection may grow.
+    pub max_size: u64,
+
+    /// Current size (in bytes) occupied by the collection.
+    pub size: u64,
+}
+
+/// Namespace/operation filters to narrow down which oplog entries a query
+/// considers.
+#[derive(Debug, Clone, Default)]
+pub struct OplogFilters {
+    /// Only include entries whose `ns` matches one of these. Each value is
+    /// an exact `database.collection` string, unless wrapped in slashes
+    /// (`/pattern/`), in which case it is a regular expression.
+    pub ns: Vec<String>,
+
+    /// Only include entries whose `op` is one of these (e.g. `"i"`, `"u"`,
+    /// `"d"`, `"c"`, `"n"`).
+    pub op: Vec<String>,
+}
+
+impl OplogFilters {
+    /// Builds the server-side filter document equivalent to these filters
+    /// (`{}` when both lists are empty).
+    fn to_document(&self) -> Document {
+        let mut filter = doc! {};
+        if !self.ns.is_empty() {
+            let ns_values: Vec<Bson> = self.ns.iter().map(|ns| ns_filter_value(ns)).collect();
+            filter.insert("ns", doc! {"$in": ns_values});
+        }
+        if !self.op.is_empty() {
+            filter.insert("op", doc! {"$in": self.op.clone()});
+        }
+        filter
+    }
+}
+
+/// Turns a single `--ns` value into the BSON value used to match it: an
+/// exact string by default, or a regular expression when the value opts
+/// into one by being wrapped in slashes (`/pattern/`).
+///
+/// Namespaces are `database.collection`, i.e. they virtually always contain
+/// a `.`, so treating any metacharacter-looking value as a regex would turn
+/// every `--ns` into an unanchored pattern (`mydb.mycoll` would then also
+/// match `mydb.mycoll_archive`). Requiring an explicit `/.../ ` opt-in keeps
+/// plain namespaces an exact match.
+fn ns_filter_value(ns: &str) -> Bson {
+    match ns.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+        Some(pattern) => Bson::RegularExpression(Regex {
+            pattern: pattern.to_owned(),
+            options: String::new(),
+        }),
+        None => Bson::String(ns.to_owned()),
+    }
+}
 
 /// Access to a MongoDB instance.
 #[derive(Debug)]
@@ -24,31 +92,56 @@ pub struct MongoDB {
 impl MongoDB {
     /// Creates an access to a MongoDB instance from the tool arguments.
     pub fn from_args(args: &Args) -> Result<Self> {
-        // Use authentication credential only if authentication has been
-        // requested by passing a username. This allows us to connect to
-        // databases that have disabled authentication.
-        let credential = if args.username.is_some() {
-            Some(
-                Credential::builder()
-                    .username(args.username.clone())
-                    .password(args.password.clone())
-                    .source(args.auth_db.clone())
-                    .build(),
-            )
+        // A full connection string takes precedence over the discrete
+        // host/port/username fields: it can express things (replica-set seed
+        // lists, TLS, read preference, SRV discovery, ...) that those fields
+        // cannot.
+        let mut client_options = if let Some(uri) = &args.uri {
+            // `ClientOptions::parse` is async by default; `.run()` is the
+            // crate's sync entry point for it, matching the blocking
+            // `mongodb::sync` client used throughout this module.
+            ClientOptions::parse(uri)
+                .run()
+                .context("failed to parse the MongoDB connection string")?
         } else {
-            None
-        };
+            // Use authentication credential only if authentication has been
+            // requested by passing a username. This allows us to connect to
+            // databases that have disabled authentication.
+            let credential = if args.username.is_some() {
+                Some(
+                    Credential::builder()
+                        .username(args.username.clone())
+                        .password(args.password.clone())
+                        .source(args.auth_db.clone())
+                        .build(),
+                )
+            } else {
+                None
+            };
 
-        let client = Client::with_options(
             ClientOptions::builder()
                 .credential(credential)
                 .hosts(vec![ServerAddress::Tcp {
                     host: args.host.clone(),
                     port: Some(args.port),
                 }])
-                .build(),
-        )
-        .context("failed to create a database client")?;
+                .build()
+        };
+
+        // The authentication mechanism is independent of how the rest of the
+        // credential was obtained, so it applies on top of either path
+        // above.
+        if let Some(mechanism) = args.auth_mechanism.clone() {
+            let mut credential = client_options
+                .credential
+                .take()
+                .unwrap_or_else(|| Credential::builder().build());
+            credential.mechanism = Some(mechanism);
+            client_options.credential = Some(credential);
+        }
+
+        let client =
+            Client::with_options(client_options).context("failed to create a database client")?;
         Ok(MongoDB { client })
     }
 
@@ -66,18 +159,133 @@ impl MongoDB {
     /// # Arguments
     ///
     /// * `limit` - The maximum number of documents to return.
-    pub fn generate_documents_in_oplog(&self, limit: u64) -> Result<Cursor<Document>> {
+    /// * `filters` - Namespace/operation filters to narrow down the query.
+    pub fn generate_documents_in_oplog(
+        &self,
+        limit: u64,
+        filters: &OplogFilters,
+    ) -> Result<Cursor<Document>> {
         let find_options = FindOptions::builder()
             .limit(limit as i64)
             .sort(doc! {"$natural": -1i32})
             .build();
         let oplog = self.get_oplog_collection();
         let cursor = oplog
-            .find(doc! {}, find_options)
+            .find(filters.to_document(), find_options)
+            .context("oplog query failed")?;
+        Ok(cursor)
+    }
+
+    /// Returns a cursor over per-`{ns, op}` group results computed
+    /// server-side, summing document counts and raw BSON sizes with a
+    /// `$group` aggregation instead of transferring every document.
+    ///
+    /// This relies on the `$bsonSize` aggregation operator (MongoDB 4.4+);
+    /// on older servers the query fails and callers should fall back to
+    /// [`generate_documents_in_oplog`](Self::generate_documents_in_oplog).
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of (most recent) documents to
+    ///   aggregate over.
+    /// * `filters` - Namespace/operation filters to narrow down the query.
+    pub fn generate_oplog_stats_aggregation(
+        &self,
+        limit: u64,
+        filters: &OplogFilters,
+    ) -> Result<Cursor<Document>> {
+        let mut pipeline = Vec::new();
+        let filter = filters.to_document();
+        if !filter.is_empty() {
+            pipeline.push(doc! {"$match": filter});
+        }
+        pipeline.push(doc! {"$limit": limit as i64});
+        pipeline.push(doc! {"$group": {
+            "_id": {"ns": "$ns", "op": "$op"},
+            "doc_count": {"$sum": 1i32},
+            "doc_total_size": {"$sum": {"$bsonSize": "$$ROOT"}},
+            "min_ts": {"$min": "$ts"},
+            "max_ts": {"$max": "$ts"},
+        }});
+        // `$natural` is only valid as a hint, not as a `$sort` key (field
+        // names may not start with `$`), so the most-recent-first order is
+        // requested via the aggregate options instead of a `$sort` stage.
+        let aggregate_options = AggregateOptions::builder()
+            .hint(Hint::Keys(doc! {"$natural": -1i32}))
+            .build();
+        let oplog = self.get_oplog_collection();
+        let cursor = oplog
+            .aggregate(pipeline, aggregate_options)
+            .context("oplog aggregation failed")?;
+        Ok(cursor)
+    }
+
+    /// Returns a tailable, awaiting cursor over the oplog that yields
+    /// documents as they are appended, starting right after the most recent
+    /// entry already present.
+    ///
+    /// Unlike [`generate_documents_in_oplog`](Self::generate_documents_in_oplog),
+    /// this cursor is never exhausted: once the oplog has been drained, the
+    /// server keeps the cursor open and blocks until new documents arrive or
+    /// it times out, so callers should keep pulling from it in a loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `filters` - Namespace/operation filters to narrow down the query.
+    pub fn generate_documents_in_oplog_tailing(
+        &self,
+        filters: &OplogFilters,
+    ) -> Result<Cursor<Document>> {
+        let start_ts = self
+            .get_most_recent_ts()
+            .context("failed to get the most recent oplog timestamp")?;
+        let mut filter = filters.to_document();
+        if let Some(ts) = start_ts {
+            filter.insert("ts", doc! {"$gt": ts});
+        }
+        let find_options = FindOptions::builder()
+            .cursor_type(CursorType::TailableAwait)
+            .sort(doc! {"$natural": 1i32})
+            .build();
+        let oplog = self.get_oplog_collection();
+        let cursor = oplog
+            .find(filter, find_options)
             .context("oplog query failed")?;
         Ok(cursor)
     }
 
+    /// Returns the `ts` of the most recent document in the oplog, or `None`
+    /// when the oplog is empty.
+    fn get_most_recent_ts(&self) -> Result<Option<bson::Bson>> {
+        let find_options = FindOptions::builder()
+            .limit(1)
+            .sort(doc! {"$natural": -1i32})
+            .build();
+        let oplog = self.get_oplog_collection();
+        let mut cursor = oplog
+            .find(doc! {}, find_options)
+            .context("oplog query failed")?;
+        let newest = match cursor.next() {
+            Some(result) => Some(result.context("oplog query failed")?),
+            None => None,
+        };
+        Ok(newest.and_then(|doc| doc.get("ts").cloned()))
+    }
+
+    /// Returns capped-collection statistics (`maxSize`, current `size`) for
+    /// the oplog, used to estimate the replication window.
+    pub fn get_oplog_coll_stats(&self) -> Result<OplogCollStats> {
+        let db = self.client.database("local");
+        let reply = db
+            .run_command(doc! {"collStats": "oplog.rs"}, None)
+            .context("collStats command failed")?;
+        let max_size = get_numeric_field(&reply, "maxSize")
+            .context("missing or non-numeric 'maxSize' entry in collStats reply")?;
+        let size = get_numeric_field(&reply, "size")
+            .context("missing or non-numeric 'size' entry in collStats reply")?;
+        Ok(OplogCollStats { max_size, size })
+    }
+
     /// Returns access to the oplog.
     fn get_oplog_collection(&self) -> Collection<Document> {
         let db = self.client.database("local");
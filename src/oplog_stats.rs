@@ -1,23 +1,122 @@
 //! Statistics of a MongoDB oplog.
 
 use std::collections::HashMap;
+use std::str::FromStr;
 
+use bson::Bson;
 use bson::Document;
+use chrono::DateTime;
+use chrono::Utc;
+use failure::format_err;
 use failure::ResultExt;
 use humansize::FileSize;
 use prettytable::cell;
 use prettytable::row;
 use prettytable::Table;
+use serde::Serialize;
 
 use crate::error::Result;
+use crate::mongodb::OplogCollStats;
 use crate::utils::compute_percentage;
+use crate::utils::format_duration_secs;
+use crate::utils::get_numeric_field;
 
 /// Type representing names of oplog entries.
 type OplogEntryName = String;
 
+/// Format in which statistics are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A human-readable table (the default).
+    Table,
+
+    /// A JSON array of per-entry records, followed by a separate JSON object
+    /// with the totals summary.
+    Json,
+
+    /// A CSV table with a header row, followed by a blank line and a
+    /// `metric,value` totals table.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+/// Prints a human-oriented status/progress line: to stdout for the default
+/// table format, or to stderr for the machine-readable formats, so that
+/// piping `--format json`/`csv` output (e.g. into `jq`, or into a file for
+/// diffing) doesn't get interleaved with free-form text.
+pub fn print_status_line(format: OutputFormat, line: &str) {
+    if format == OutputFormat::Table {
+        println!("{}", line);
+    } else {
+        eprintln!("{}", line);
+    }
+}
+
+/// A serializable snapshot of the statistics for a single oplog entry, meant
+/// for the machine-readable output formats.
+#[derive(Debug, Serialize)]
+pub struct OplogEntryRecord {
+    /// Name of the entry (`{ns}:{op}`).
+    pub entry: OplogEntryName,
+
+    /// Database and collection which the entry applies to.
+    pub ns: String,
+
+    /// Performed operation (e.g. "i" for insertion).
+    pub op: String,
+
+    /// Number of documents for the entry.
+    pub doc_count: u64,
+
+    /// Total size of documents for the entry, in raw bytes.
+    pub total_size_bytes: u64,
+
+    /// Share (percentage) of this entry's total size among all entries.
+    pub share_percent: f64,
+}
+
+/// A serializable summary across all oplog entries, meant to accompany the
+/// per-entry [`OplogEntryRecord`]s in the machine-readable output formats.
+#[derive(Debug, Serialize)]
+pub struct OplogStatsTotals {
+    /// Total number of documents processed so far, across all entries.
+    pub doc_count: u64,
+
+    /// Total size (in raw bytes) of all processed documents, across all
+    /// entries.
+    pub total_size_bytes: u64,
+
+    /// Timestamp (RFC 3339) of the oldest entry seen so far, when any.
+    pub oldest_entry: Option<String>,
+
+    /// Timestamp (RFC 3339) of the newest entry seen so far, when any.
+    pub newest_entry: Option<String>,
+
+    /// Wall-clock time span covered by the entries seen so far, in seconds.
+    pub covered_span_seconds: Option<i64>,
+}
+
 /// Statistics for a single oplog entry.
 #[derive(Debug)]
 struct OplogEntryStats {
+    /// Database and collection which the entry applies to.
+    ns: String,
+
+    /// Performed operation (e.g. "i" for insertion).
+    op: String,
+
     /// Number of documents for the entry.
     doc_count: u64,
 
@@ -27,8 +126,10 @@ struct OplogEntryStats {
 
 impl OplogEntryStats {
     /// Creates a new, empty statistics for a single oplog entry.
-    fn new() -> Self {
+    fn new(ns: String, op: String) -> Self {
         OplogEntryStats {
+            ns,
+            op,
             doc_count: 0,
             doc_total_size: 0,
         }
@@ -43,6 +144,14 @@ pub struct OplogStats {
 
     /// Number of processed documents so far.
     processed_doc_count: u64,
+
+    /// Seconds-since-epoch part of the `ts` of the oldest document seen so
+    /// far.
+    min_ts_secs: Option<i64>,
+
+    /// Seconds-since-epoch part of the `ts` of the newest document seen so
+    /// far.
+    max_ts_secs: Option<i64>,
 }
 
 impl OplogStats {
@@ -51,6 +160,8 @@ impl OplogStats {
         OplogStats {
             stats: HashMap::new(),
             processed_doc_count: 0,
+            min_ts_secs: None,
+            max_ts_secs: None,
         }
     }
 
@@ -59,21 +170,81 @@ impl OplogStats {
         let doc_size = self
             .doc_size(doc)
             .context("failed to get the size of a document")?;
-        let entry_name = self
-            .entry_name_for_doc(doc)
-            .context("failed to get an entry name for a document")?;
+        let (ns, op) = self
+            .ns_and_op_for_doc(doc)
+            .context("failed to get the namespace and operation of a document")?;
+        let entry_name = format!("{}:{}", ns, op);
 
         let mut value = self
             .stats
             .entry(entry_name)
-            .or_insert_with(OplogEntryStats::new);
+            .or_insert_with(|| OplogEntryStats::new(ns, op));
         value.doc_count += 1;
         value.doc_total_size += doc_size;
 
+        if let Some(ts_secs) = self
+            .ts_secs_for_doc(doc)
+            .context("failed to get the timestamp of a document")?
+        {
+            self.observe_ts_secs(ts_secs);
+        }
+
         self.processed_doc_count += 1;
         Ok(())
     }
 
+    /// Merges a single pre-aggregated `{ns, op}` group result, as produced by
+    /// the aggregation-based collection path, into the statistics.
+    pub fn merge_aggregated(&mut self, group: &Document) -> Result<()> {
+        let id = group
+            .get_document("_id")
+            .context("missing '_id' entry in aggregation result")?;
+        let ns = id
+            .get_str("ns")
+            .context("missing 'ns' entry in aggregation result")?
+            .to_owned();
+        let op = id
+            .get_str("op")
+            .context("missing 'op' entry in aggregation result")?
+            .to_owned();
+        // `$sum` accumulators reply as Int32, Int64, or Double depending on
+        // the magnitude of the total, so a single-type `get_i64` would
+        // error on the common case of small, well within Int32, counts.
+        let doc_count = get_numeric_field(group, "doc_count")
+            .context("missing or non-numeric 'doc_count' entry in aggregation result")?;
+        let doc_total_size = get_numeric_field(group, "doc_total_size")
+            .context("missing or non-numeric 'doc_total_size' entry in aggregation result")?;
+
+        let entry_name = format!("{}:{}", ns, op);
+        let mut value = self
+            .stats
+            .entry(entry_name)
+            .or_insert_with(|| OplogEntryStats::new(ns, op));
+        value.doc_count += doc_count;
+        value.doc_total_size += doc_total_size;
+
+        if let Some(min_ts) = group.get("min_ts") {
+            let ts_secs = ts_secs_from_bson(min_ts)
+                .context("failed to get 'min_ts' from an aggregation result")?;
+            self.observe_ts_secs(ts_secs);
+        }
+        if let Some(max_ts) = group.get("max_ts") {
+            let ts_secs = ts_secs_from_bson(max_ts)
+                .context("failed to get 'max_ts' from an aggregation result")?;
+            self.observe_ts_secs(ts_secs);
+        }
+
+        self.processed_doc_count += doc_count;
+        Ok(())
+    }
+
+    /// Widens the observed `[min_ts_secs, max_ts_secs]` range to include the
+    /// given timestamp.
+    fn observe_ts_secs(&mut self, ts_secs: i64) {
+        self.min_ts_secs = Some(self.min_ts_secs.map_or(ts_secs, |min| min.min(ts_secs)));
+        self.max_ts_secs = Some(self.max_ts_secs.map_or(ts_secs, |max| max.max(ts_secs)));
+    }
+
     /// Returns the number of processed documents so far.
     pub fn get_processed_doc_count(&self) -> u64 {
         self.processed_doc_count
@@ -84,8 +255,17 @@ impl OplogStats {
         self.processed_doc_count > 0
     }
 
-    /// Prints the statistics in nicely formatted table to the standard output.
-    pub fn print(&self) {
+    /// Prints the statistics to the standard output in the given format.
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Table => self.print_table(),
+            OutputFormat::Json => self.print_json(),
+            OutputFormat::Csv => self.print_csv(),
+        }
+    }
+
+    /// Prints the statistics in a nicely formatted table.
+    fn print_table(&self) {
         let mut table = Table::new();
         table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
         table.set_titles(row!["Entry", "Documents", "Total size", "Share (%)"]);
@@ -100,6 +280,148 @@ impl OplogStats {
         table.printstd();
     }
 
+    /// Prints the statistics as JSON: an array of per-entry records (as
+    /// specified), followed by a second, separate JSON value with the
+    /// totals summary. Concatenated JSON values are a stream `jq` (and
+    /// `serde_json::Deserializer::into_iter`) can read directly, so the
+    /// entries array itself stays exactly the documented shape.
+    fn print_json(&self) {
+        match serde_json::to_string_pretty(&self.records()) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("failed to serialize statistics into JSON: {}", err),
+        }
+        match serde_json::to_string_pretty(&self.totals()) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("failed to serialize totals into JSON: {}", err),
+        }
+    }
+
+    /// Prints the statistics as CSV: a header row followed by one line per
+    /// entry, then a blank line and a `metric,value` totals summary.
+    ///
+    /// `ns` (and `entry`, which embeds it) is written unescaped: collection
+    /// names can legally contain commas, which would make such a row
+    /// misalign under a naive CSV parser. Not handled here since oplog
+    /// namespaces in practice don't, but worth keeping in mind if that
+    /// assumption ever stops holding.
+    fn print_csv(&self) {
+        println!("entry,ns,op,doc_count,total_size_bytes,share_percent");
+        for record in self.records() {
+            println!(
+                "{},{},{},{},{},{}",
+                record.entry,
+                record.ns,
+                record.op,
+                record.doc_count,
+                record.total_size_bytes,
+                record.share_percent,
+            );
+        }
+
+        let totals = self.totals();
+        println!();
+        println!("metric,value");
+        println!("doc_count,{}", totals.doc_count);
+        println!("total_size_bytes,{}", totals.total_size_bytes);
+        println!("oldest_entry,{}", totals.oldest_entry.unwrap_or_default());
+        println!("newest_entry,{}", totals.newest_entry.unwrap_or_default());
+        println!(
+            "covered_span_seconds,{}",
+            totals
+                .covered_span_seconds
+                .map(|secs| secs.to_string())
+                .unwrap_or_default()
+        );
+    }
+
+    /// Returns the totals summary across all entries.
+    fn totals(&self) -> OplogStatsTotals {
+        OplogStatsTotals {
+            doc_count: self.processed_doc_count,
+            total_size_bytes: self.total_doc_size_for_all_entries(),
+            oldest_entry: self.oldest_entry_time().map(|dt| dt.to_rfc3339()),
+            newest_entry: self.newest_entry_time().map(|dt| dt.to_rfc3339()),
+            covered_span_seconds: self.covered_span_secs(),
+        }
+    }
+
+    /// Renders the current statistics in Prometheus/OpenMetrics text
+    /// exposition format, suitable for serving at `/metrics`.
+    pub fn to_prometheus_text(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP oplog_entry_documents Number of oplog documents for a namespace and operation.\n");
+        out.push_str("# TYPE oplog_entry_documents gauge\n");
+        for record in self.records() {
+            out.push_str(&format!(
+                "oplog_entry_documents{{ns=\"{}\",op=\"{}\"}} {}\n",
+                escape_label_value(&record.ns),
+                escape_label_value(&record.op),
+                record.doc_count
+            ));
+        }
+
+        out.push_str("# HELP oplog_entry_total_bytes Total raw BSON size of oplog documents for a namespace and operation.\n");
+        out.push_str("# TYPE oplog_entry_total_bytes gauge\n");
+        for record in self.records() {
+            out.push_str(&format!(
+                "oplog_entry_total_bytes{{ns=\"{}\",op=\"{}\"}} {}\n",
+                escape_label_value(&record.ns),
+                escape_label_value(&record.op),
+                record.total_size_bytes
+            ));
+        }
+
+        out.push_str("# HELP oplog_entry_share_percent Share (percentage) of an entry's total size among all entries.\n");
+        out.push_str("# TYPE oplog_entry_share_percent gauge\n");
+        for record in self.records() {
+            out.push_str(&format!(
+                "oplog_entry_share_percent{{ns=\"{}\",op=\"{}\"}} {}\n",
+                escape_label_value(&record.ns),
+                escape_label_value(&record.op),
+                record.share_percent
+            ));
+        }
+
+        // Named (and kept) as requested for dashboards that already key off
+        // `oplog_processed_documents_total`, but intentionally typed as a
+        // gauge rather than a counter: each scrape recomputes this from the
+        // currently observed (bounded by `--limit`) window, so it can go
+        // down as well as up rather than only ever increasing.
+        out.push_str("# HELP oplog_processed_documents_total Number of oplog documents in the currently observed window.\n");
+        out.push_str("# TYPE oplog_processed_documents_total gauge\n");
+        out.push_str(&format!(
+            "oplog_processed_documents_total {}\n",
+            self.processed_doc_count
+        ));
+
+        if let Some(span_secs) = self.covered_span_secs() {
+            out.push_str("# HELP oplog_window_seconds Wall-clock time span covered by the currently observed oplog entries.\n");
+            out.push_str("# TYPE oplog_window_seconds gauge\n");
+            out.push_str(&format!("oplog_window_seconds {}\n", span_secs));
+        }
+
+        out
+    }
+
+    /// Returns the current statistics as serializable per-entry records,
+    /// ordered by their share (in a descending order), with raw byte sizes
+    /// rather than the humansize-formatted strings used by the table
+    /// renderer.
+    fn records(&self) -> Vec<OplogEntryRecord> {
+        self.most_common()
+            .into_iter()
+            .map(|(entry_name, entry_stats)| OplogEntryRecord {
+                entry: entry_name.clone(),
+                ns: entry_stats.ns.clone(),
+                op: entry_stats.op.clone(),
+                doc_count: entry_stats.doc_count,
+                total_size_bytes: entry_stats.doc_total_size,
+                share_percent: self.share_for(entry_name),
+            })
+            .collect()
+    }
+
     /// Formats the given document size into a human-readable string.
     fn format_total_doc_size(&self, size: u64) -> String {
         size.file_size(humansize::file_size_opts::CONVENTIONAL)
@@ -124,8 +446,97 @@ impl OplogStats {
         Ok(bytes.len() as u64)
     }
 
-    /// Returns the name of an entry for the given oplog document.
-    fn entry_name_for_doc(&self, doc: &Document) -> Result<OplogEntryName> {
+    /// Returns the seconds-since-epoch part of the `ts` of the given oplog
+    /// document, or `None` when the document has no `ts` entry.
+    ///
+    /// The `ts` entry is usually a BSON `Timestamp`, whose high 32 bits are
+    /// seconds since epoch, but some code paths (e.g. aggregation results)
+    /// may surface it as a plain `DateTime` instead, so both are handled.
+    fn ts_secs_for_doc(&self, doc: &Document) -> Result<Option<i64>> {
+        match doc.get("ts") {
+            Some(ts) => Ok(Some(ts_secs_from_bson(ts)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the timestamp of the oldest oplog entry seen so far.
+    pub fn oldest_entry_time(&self) -> Option<DateTime<Utc>> {
+        self.min_ts_secs.map(ts_secs_to_datetime)
+    }
+
+    /// Returns the timestamp of the newest oplog entry seen so far.
+    pub fn newest_entry_time(&self) -> Option<DateTime<Utc>> {
+        self.max_ts_secs.map(ts_secs_to_datetime)
+    }
+
+    /// Returns the span of wall-clock time covered by the entries seen so
+    /// far, in seconds.
+    pub fn covered_span_secs(&self) -> Option<i64> {
+        match (self.min_ts_secs, self.max_ts_secs) {
+            (Some(min), Some(max)) => Some(max - min),
+            _ => None,
+        }
+    }
+
+    /// Prints the estimated replication window: how much wall-clock history
+    /// the oplog currently holds and, when `coll_stats` is available, an
+    /// estimate of the total window once the capped collection is full.
+    ///
+    /// This is status text rather than structured data, so for the
+    /// machine-readable formats it is printed to stderr instead of stdout
+    /// (see [`print_status_line`]).
+    pub fn print_replication_window(&self, coll_stats: Option<&OplogCollStats>, format: OutputFormat) {
+        let (oldest, newest, span_secs) =
+            match (self.oldest_entry_time(), self.newest_entry_time(), self.covered_span_secs()) {
+                (Some(oldest), Some(newest), Some(span_secs)) => (oldest, newest, span_secs),
+                _ => {
+                    print_status_line(format, "Replication window: oplog is empty, no timestamps observed yet");
+                    return;
+                }
+            };
+
+        print_status_line(format, &format!("Oldest entry: {}", oldest));
+        print_status_line(format, &format!("Newest entry: {}", newest));
+        print_status_line(
+            format,
+            &format!("Covered span: {}", format_duration_secs(span_secs)),
+        );
+
+        match coll_stats {
+            Some(coll_stats) if coll_stats.size > 0 && coll_stats.max_size > 0 => {
+                let fill_ratio = coll_stats.size as f64 / coll_stats.max_size as f64;
+                if fill_ratio >= 0.999 {
+                    let window_secs =
+                        (span_secs as f64 * (coll_stats.max_size as f64 / coll_stats.size as f64)) as i64;
+                    print_status_line(
+                        format,
+                        &format!(
+                            "Estimated full window: {} (oplog is full)",
+                            format_duration_secs(window_secs)
+                        ),
+                    );
+                } else {
+                    print_status_line(
+                        format,
+                        &format!(
+                            "Oplog not yet full ({:.1}% of {} used); window will keep growing",
+                            fill_ratio * 100.0,
+                            self.format_total_doc_size(coll_stats.max_size)
+                        ),
+                    );
+                }
+            }
+            _ => {
+                print_status_line(
+                    format,
+                    "Estimated full window: unknown (capped collection stats unavailable)",
+                );
+            }
+        }
+    }
+
+    /// Returns the namespace and operation of the given oplog document.
+    fn ns_and_op_for_doc(&self, doc: &Document) -> Result<(String, String)> {
         // Database and collection which the oplog entry applies to.
         let ns = doc
             .get_str("ns")
@@ -138,8 +549,7 @@ impl OplogStats {
             .context("missing 'op' entry in oplog document")?
             .to_owned();
 
-        let name = format!("{}:{}", ns, op);
-        Ok(name)
+        Ok((ns, op))
     }
 
     /// Returns oplog entries ordered by their share (in a descending order).
@@ -166,3 +576,26 @@ impl OplogStats {
         self.stats.values().map(|v| v.doc_total_size).sum()
     }
 }
+
+/// Converts seconds since epoch into a UTC date and time.
+fn ts_secs_to_datetime(ts_secs: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_utc(chrono::NaiveDateTime::from_timestamp(ts_secs, 0), Utc)
+}
+
+/// Extracts the seconds-since-epoch part out of a BSON `ts` value.
+///
+/// The `ts` entry is usually a BSON `Timestamp`, whose high 32 bits are
+/// seconds since epoch, but some code paths (e.g. aggregation results) may
+/// surface it as a plain `DateTime` instead, so both are handled.
+fn ts_secs_from_bson(bson: &Bson) -> Result<i64> {
+    match bson {
+        Bson::Timestamp(ts) => Ok(i64::from(ts.time)),
+        Bson::DateTime(dt) => Ok(dt.timestamp()),
+        _ => Err(format_err!("'ts' entry has an unexpected type")),
+    }
+}
+
+/// Escapes backslashes and double quotes in a Prometheus label value.
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,77 @@
+//! Exposes oplog statistics as a Prometheus/OpenMetrics HTTP endpoint.
+
+use failure::format_err;
+use tiny_http::Header;
+use tiny_http::Response;
+use tiny_http::Server;
+
+use crate::error::Result;
+use crate::mongodb::MongoDB;
+use crate::mongodb::OplogFilters;
+use crate::obtain_oplog_stats_preferring_aggregation;
+use crate::oplog_stats::OplogStats;
+use crate::oplog_stats::OutputFormat;
+
+/// Runs an HTTP server on `addr` exposing the oplog statistics at
+/// `/metrics` in Prometheus text exposition format. The statistics are
+/// recomputed from scratch on every scrape by re-querying the oplog, so
+/// scrapes always see fresh numbers. Never returns on its own.
+///
+/// # Arguments
+///
+/// * `addr` - Address (`host:port`) to listen on.
+/// * `mongodb` - Access to a MongoDB instance.
+/// * `limit` - Maximal number of (most recent) documents to consider on
+///   each scrape.
+/// * `filters` - Namespace/operation filters to narrow down the query.
+pub fn serve(addr: &str, mongodb: &MongoDB, limit: u64, filters: &OplogFilters) -> Result<()> {
+    let server = Server::http(addr)
+        .map_err(|err| format_err!("failed to bind the HTTP server to {}: {}", addr, err))?;
+    println!("Serving oplog metrics on http://{}/metrics", addr);
+
+    for request in server.incoming_requests() {
+        let response = if request.url() == "/metrics" {
+            match refresh_oplog_stats(mongodb, limit, filters) {
+                Ok(oplog_stats) => {
+                    let header = Header::from_bytes(
+                        &b"Content-Type"[..],
+                        &b"text/plain; version=0.0.4"[..],
+                    )
+                    .expect("static header should always be valid");
+                    Response::from_string(oplog_stats.to_prometheus_text()).with_header(header)
+                }
+                Err(err) => {
+                    Response::from_string(format!("error: {}\n", err)).with_status_code(500)
+                }
+            }
+        } else {
+            Response::from_string("not found\n").with_status_code(404)
+        };
+
+        if let Err(err) = request.respond(response) {
+            eprintln!("warning: failed to respond to a scrape request: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Computes fresh oplog statistics for a single scrape, preferring the fast
+/// aggregation path and transparently falling back to streaming every
+/// document on servers too old to support it, same as the one-shot CLI
+/// path.
+fn refresh_oplog_stats(
+    mongodb: &MongoDB,
+    limit: u64,
+    filters: &OplogFilters,
+) -> Result<OplogStats> {
+    let mut oplog_stats = OplogStats::new();
+    obtain_oplog_stats_preferring_aggregation(
+        &mut oplog_stats,
+        mongodb,
+        limit,
+        None,
+        OutputFormat::Table,
+        filters,
+    )?;
+    Ok(oplog_stats)
+}
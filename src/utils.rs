@@ -1,6 +1,37 @@
 //! Utilities.
 
+use bson::Document;
+use failure::format_err;
+
+use crate::error::Result;
+
 /// Computes the percentage of `part` to `total`.
 pub fn compute_percentage(part: f64, total: f64) -> f64 {
     (part * 100.0) / total
 }
+
+/// Reads `key` out of `doc` as a `u64`, accepting whichever numeric BSON
+/// type the server happened to reply with.
+///
+/// MongoDB servers reply with Int32, Int64, or Double for numeric fields
+/// depending on the field's magnitude (e.g. `collStats`'s `size`/`maxSize`)
+/// or on how a `$group` accumulator happened to compute it (e.g. `$sum`),
+/// so a single `get_i64` would error on the common case.
+pub fn get_numeric_field(doc: &Document, key: &str) -> Result<u64> {
+    doc.get_i64(key)
+        .map(|n| n as u64)
+        .or_else(|_| doc.get_i32(key).map(|n| n as u64))
+        .or_else(|_| doc.get_f64(key).map(|n| n as u64))
+        .map_err(|_| format_err!("'{}' entry has an unexpected (non-numeric) type", key))
+}
+
+/// Formats a duration given in seconds as a human-readable string, e.g.
+/// `"2d 3h 15m 0s"`.
+pub fn format_duration_secs(total_secs: i64) -> String {
+    let total_secs = total_secs.max(0);
+    let days = total_secs / (24 * 60 * 60);
+    let hours = (total_secs / (60 * 60)) % 24;
+    let minutes = (total_secs / 60) % 60;
+    let secs = total_secs % 60;
+    format!("{}d {}h {}m {}s", days, hours, minutes, secs)
+}